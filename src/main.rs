@@ -1,25 +1,45 @@
 use std::env;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufRead, BufWriter, Read, Write};
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Firmware flavour that decides which command pauses the print for a manual
+/// filament/color change. The single-nozzle rewriting logic is identical across
+/// all of them; only the emitted change command differs.
+enum Firmware {
+    Marlin,
+    RepRap,
+    Klipper,
+    Custom(Vec<String>),
+}
+
+impl Firmware {
+    /// Multi-line command sequence written in place of a toolchange block.
+    fn pause_sequence(&self) -> Vec<String> {
+        match self {
+            // Both Marlin and RepRap understand the `M600` filament
+            // change command.
+            Firmware::Marlin | Firmware::RepRap => vec!["M600".to_string()],
+            Firmware::Klipper => vec!["PAUSE".to_string()],
+            Firmware::Custom(lines) => lines.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
 struct SlicerConfig {
     wipe_tower: bool,
     total_toolchanges: u32,
+    temperatures: Vec<u32>,
+    first_layer_temperatures: Vec<u32>,
 }
 
 impl SlicerConfig {
-    pub fn from_file(file: &File) -> io::Result<SlicerConfig> {
-        let reader = BufReader::new(file);
-        Self::read(reader)
-    }
-
     pub fn read(reader: impl BufRead) -> io::Result<SlicerConfig> {
-        let mut config = SlicerConfig {
-            wipe_tower: false,
-            total_toolchanges: 0,
-        };
+        let mut config = SlicerConfig::default();
 
         for line in reader.lines() {
             let line = line?;
@@ -45,20 +65,177 @@ impl SlicerConfig {
             };
             return;
         }
+
+        // Per-extruder temperatures live in the prusaslicer_config footer as a
+        // comma-separated list, one entry per tool index. Keys are matched on the
+        // full `; <key> = ` prefix, so `first_layer_temperature` never matches
+        // the `temperature` entry.
+        if let Some(val) = line.strip_prefix("; first_layer_temperature = ") {
+            self.first_layer_temperatures = parse_temperature_list(val);
+            return;
+        }
+        if let Some(val) = line.strip_prefix("; temperature = ") {
+            self.temperatures = parse_temperature_list(val);
+        }
+    }
+
+    /// Whether any per-extruder temperature data was found in the footer. When
+    /// false the toolchange rewriters fall back to emitting the bare pause
+    /// sequence.
+    fn has_temperatures(&self) -> bool {
+        !self.temperatures.is_empty() || !self.first_layer_temperatures.is_empty()
+    }
+
+    /// Target temperature for `tool`, preferring the first-layer table while on
+    /// layer 0. Returns `None` when no value is configured for that index.
+    fn target_temperature(&self, tool: usize, first_layer: bool) -> Option<u32> {
+        let table = if first_layer && !self.first_layer_temperatures.is_empty() {
+            &self.first_layer_temperatures
+        } else {
+            &self.temperatures
+        };
+        table.get(tool).copied()
+    }
+}
+
+fn parse_temperature_list(val: &str) -> Vec<u32> {
+    val.split(',')
+        .filter_map(|entry| entry.trim().parse().ok())
+        .collect()
+}
+
+/// Source/destination materials carried out of a toolchange block so the
+/// rewriters can decide whether a temperature change is actually needed.
+#[derive(Default)]
+struct PendingToolchange {
+    src_material: Option<String>,
+    dst_material: Option<String>,
+}
+
+impl PendingToolchange {
+    fn read_material(&mut self, line: &str) {
+        if let Some(val) = line.strip_prefix("; material :") {
+            let mut parts = val.split("->");
+            if let Some(src) = parts.next() {
+                self.src_material = Some(src.trim().to_string());
+            }
+            if let Some(dst) = parts.next() {
+                self.dst_material = Some(dst.trim().to_string());
+            }
+        }
+    }
+
+    fn material_changed(&self) -> bool {
+        match (&self.src_material, &self.dst_material) {
+            (Some(src), Some(dst)) => src != dst,
+            // Without a `; material` line we cannot tell, so assume a change.
+            _ => true,
+        }
+    }
+}
+
+/// Tool index of a bare `Tn` move (e.g. `T1`), or `None` for other lines.
+fn parse_tool_index(line: &str) -> Option<usize> {
+    let rest = line.strip_prefix('T')?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// First layer while fewer than two `;LAYER_CHANGE` markers have been seen: the
+/// first marker opens layer 0, the second opens layer 1. PrusaSlicer also emits
+/// a `;HEIGHT:` comment, but unlike `;LAYER_CHANGE` it recurs within a layer
+/// (e.g. on every extrusion move), so it isn't a reliable layer boundary and
+/// is intentionally not tracked here.
+fn is_first_layer(layer_changes: u32) -> bool {
+    layer_changes < 2
+}
+
+/// Emit the change command for a completed toolchange. With temperature data
+/// for `tool` and a differing material this wraps the pause sequence in
+/// `M104`/`M109` so the nozzle reaches the destination temperature before
+/// printing resumes; otherwise it falls back to the bare pause sequence.
+/// Whether the material differs is decided from the block's `; material` line
+/// only (a missing line counts as a change); source and destination tool
+/// indices are not compared, `tool` only selects the target temperature.
+fn write_change(
+    writer: &mut impl Write,
+    config: &SlicerConfig,
+    pause: &[String],
+    pending: &PendingToolchange,
+    tool: Option<usize>,
+    first_layer: bool,
+) -> io::Result<()> {
+    let target = tool.and_then(|t| config.target_temperature(t, first_layer));
+
+    if let (Some(temp), true) = (target, pending.material_changed()) {
+        writeln!(writer, "M104 S{}", temp)?;
+        write_sequence(writer, pause)?;
+        writeln!(writer, "M109 S{}", temp)?;
+    } else {
+        write_sequence(writer, pause)?;
+    }
+
+    Ok(())
+}
+
+fn write_sequence(writer: &mut impl Write, pause: &[String]) -> io::Result<()> {
+    for command in pause {
+        writeln!(writer, "{}", command)?;
     }
+    Ok(())
+}
+
+/// Emit a bare change command for an outstanding `pending` toolchange whose
+/// `Tn` never showed up (e.g. another block started before it did), so a
+/// change is never silently dropped when `pending` is about to be overwritten
+/// or the file ends.
+fn flush_pending(
+    writer: &mut impl Write,
+    pause: &[String],
+    pending: &mut Option<PendingToolchange>,
+) -> io::Result<()> {
+    if pending.take().is_some() {
+        write_sequence(writer, pause)?;
+    }
+    Ok(())
 }
 
 fn replace_unloads(
     reader: impl BufRead,
     writer: &mut impl Write,
-    total_toolchanges: u32,
+    config: &SlicerConfig,
+    pause: &[String],
 ) -> io::Result<()> {
+    let temperature_aware = config.has_temperatures();
     let mut skip_block = false;
     let mut toolchanges = 0;
+    let mut layer_changes = 0;
+    let mut pending: Option<PendingToolchange> = None;
+    let mut current = PendingToolchange::default();
 
     for line in reader.lines() {
         let line = line?;
 
+        if line.starts_with(";LAYER_CHANGE") {
+            layer_changes += 1;
+        }
+        current.read_material(&line);
+
+        // A temperature-aware change is emitted once the incoming `Tn` move is
+        // known, which normally follows the block.
+        if pending.is_some() {
+            if let Some(tool) = parse_tool_index(&line) {
+                let change = pending.take().unwrap();
+                write_change(
+                    writer,
+                    config,
+                    pause,
+                    &change,
+                    Some(tool),
+                    is_first_layer(layer_changes),
+                )?;
+            }
+        }
+
         // "CP TOOLCHANGE UNLOAD ... CP TOOLCHANGE WIPE" is nested inside
         // of "CP TOOLCHANGE START ... CP TOOLCHANGE END", thus checked first
         if line.starts_with("; CP TOOLCHANGE UNLOAD") {
@@ -66,7 +243,12 @@ fn replace_unloads(
             continue;
         }
         if line.starts_with("; CP TOOLCHANGE WIPE") {
-            writeln!(writer, "M600")?;
+            if temperature_aware && toolchanges <= config.total_toolchanges {
+                flush_pending(writer, pause, &mut pending)?;
+                pending = Some(std::mem::take(&mut current));
+            } else {
+                write_sequence(writer, pause)?;
+            }
             skip_block = false;
             continue;
         }
@@ -76,52 +258,240 @@ fn replace_unloads(
 
             // The last "CP TOOLCHANGE START ... CP TOOLCHANGE END" block
             // must be removed completely
-            if toolchanges > total_toolchanges {
+            if toolchanges > config.total_toolchanges {
                 skip_block = true;
                 continue;
             }
         }
-        if line.starts_with("; CP TOOLCHANGE END") {
-            if toolchanges > total_toolchanges {
-                skip_block = false;
-                continue;
-            }
+        if line.starts_with("; CP TOOLCHANGE END") && toolchanges > config.total_toolchanges {
+            skip_block = false;
+            continue;
         }
 
         if !skip_block {
             writer.write_all(line.as_bytes())?;
-            writer.write(b"\n")?;
+            writer.write_all(b"\n")?;
         }
     }
 
+    // No `Tn` followed the final block: fall back to a bare change command.
+    flush_pending(writer, pause, &mut pending)?;
+
     writer.flush()
 }
 
-fn replace_toolchanges(reader: impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+fn replace_toolchanges(
+    reader: impl BufRead,
+    writer: &mut impl Write,
+    config: &SlicerConfig,
+    pause: &[String],
+) -> io::Result<()> {
+    let temperature_aware = config.has_temperatures();
     let mut skip_block = false;
+    let mut layer_changes = 0;
+    let mut pending: Option<PendingToolchange> = None;
+    let mut current = PendingToolchange::default();
 
     for line in reader.lines() {
         let line = line?;
 
+        if line.starts_with(";LAYER_CHANGE") {
+            layer_changes += 1;
+        }
+        current.read_material(&line);
+
+        if pending.is_some() {
+            if let Some(tool) = parse_tool_index(&line) {
+                let change = pending.take().unwrap();
+                write_change(
+                    writer,
+                    config,
+                    pause,
+                    &change,
+                    Some(tool),
+                    is_first_layer(layer_changes),
+                )?;
+            }
+        }
+
         if line.starts_with("; CP TOOLCHANGE START") {
             skip_block = true;
             continue;
         }
         if line.starts_with("; CP TOOLCHANGE END") {
-            writeln!(writer, "M600")?;
+            if temperature_aware {
+                flush_pending(writer, pause, &mut pending)?;
+                pending = Some(std::mem::take(&mut current));
+            } else {
+                write_sequence(writer, pause)?;
+            }
             skip_block = false;
             continue;
         }
 
         if !skip_block {
             writer.write_all(line.as_bytes())?;
-            writer.write(b"\n")?;
+            writer.write_all(b"\n")?;
         }
     }
 
+    flush_pending(writer, pause, &mut pending)?;
+
     writer.flush()
 }
 
+/// Marker counts collected by [`validate`].
+#[derive(Debug, Default, PartialEq)]
+struct ToolchangeStats {
+    starts: u32,
+    ends: u32,
+    unloads: u32,
+    wipes: u32,
+}
+
+impl fmt::Display for ToolchangeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} start/{} end, {} unload/{} wipe marker(s)",
+            self.starts, self.ends, self.unloads, self.wipes
+        )
+    }
+}
+
+/// Structural problem found while validating toolchange markers. Line numbers
+/// are 1-based to match what an editor shows.
+#[derive(Debug)]
+enum ValidationError {
+    Io(io::Error),
+    NestedStart { line: usize },
+    UnmatchedEnd { line: usize },
+    UnterminatedStart,
+    NestedUnload { line: usize },
+    WipeWithoutUnload { line: usize },
+    CountMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Io(err) => write!(f, "{err}"),
+            ValidationError::NestedStart { line } => {
+                write!(f, "line {line}: toolchange START inside an open START block")
+            }
+            ValidationError::UnmatchedEnd { line } => {
+                write!(f, "line {line}: toolchange END without a matching START")
+            }
+            ValidationError::UnterminatedStart => {
+                write!(f, "unterminated toolchange START block at end of file")
+            }
+            ValidationError::NestedUnload { line } => {
+                write!(f, "line {line}: UNLOAD inside an open UNLOAD block")
+            }
+            ValidationError::WipeWithoutUnload { line } => {
+                write!(f, "line {line}: WIPE with no preceding UNLOAD")
+            }
+            ValidationError::CountMismatch { expected, found } => write!(
+                f,
+                "found {found} toolchange block(s) but the footer reports {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<io::Error> for ValidationError {
+    fn from(err: io::Error) -> Self {
+        ValidationError::Io(err)
+    }
+}
+
+impl From<ValidationError> for io::Error {
+    fn from(err: ValidationError) -> Self {
+        match err {
+            ValidationError::Io(err) => err,
+            other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+        }
+    }
+}
+
+/// Walk the G-code and verify that the toolchange markers are balanced before
+/// anything is rewritten, so a truncated or malformed file fails loudly instead
+/// of silently producing corrupt output.
+fn validate(reader: impl BufRead) -> Result<ToolchangeStats, ValidationError> {
+    let mut stats = ToolchangeStats::default();
+    let mut in_start = false;
+    let mut in_unload = false;
+    let mut expected_total: Option<u32> = None;
+    let mut wipe_tower = false;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let lineno = idx + 1;
+
+        // "UNLOAD"/"WIPE" are nested inside "START"/"END"; none of the four
+        // prefixes overlap, so the order of these checks is irrelevant.
+        if line.starts_with("; CP TOOLCHANGE START") {
+            if in_start {
+                return Err(ValidationError::NestedStart { line: lineno });
+            }
+            in_start = true;
+            stats.starts += 1;
+        } else if line.starts_with("; CP TOOLCHANGE END") {
+            if !in_start {
+                return Err(ValidationError::UnmatchedEnd { line: lineno });
+            }
+            // PrusaSlicer's trailing wipe-tower block has an UNLOAD with no
+            // matching WIPE before END (the rewriter drops that whole block),
+            // so END closes an open UNLOAD instead of erroring on it.
+            in_unload = false;
+            in_start = false;
+            stats.ends += 1;
+        } else if line.starts_with("; CP TOOLCHANGE UNLOAD") {
+            if in_unload {
+                return Err(ValidationError::NestedUnload { line: lineno });
+            }
+            in_unload = true;
+            stats.unloads += 1;
+        } else if line.starts_with("; CP TOOLCHANGE WIPE") {
+            if !in_unload {
+                return Err(ValidationError::WipeWithoutUnload { line: lineno });
+            }
+            in_unload = false;
+            stats.wipes += 1;
+        } else if let Some(val) = line.strip_prefix("; total toolchanges = ") {
+            if let Ok(n) = val.parse() {
+                expected_total = Some(n);
+            }
+        } else if let Some(val) = line.strip_prefix("; wipe_tower = ") {
+            wipe_tower = val == "1";
+        }
+    }
+
+    if in_start {
+        return Err(ValidationError::UnterminatedStart);
+    }
+
+    // In wipe-tower mode PrusaSlicer emits one extra trailing block that the
+    // rewriter drops, so both `total` and `total + 1` are accepted there.
+    if let Some(total) = expected_total {
+        let balanced = if wipe_tower {
+            stats.starts == total || stats.starts == total + 1
+        } else {
+            stats.starts == total
+        };
+        if !balanced {
+            return Err(ValidationError::CountMismatch {
+                expected: total,
+                found: stats.starts,
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
 fn tempfile(prefix: &str) -> io::Result<(File, PathBuf)> {
     let mut path = env::temp_dir();
 
@@ -136,39 +506,248 @@ fn tempfile(prefix: &str) -> io::Result<(File, PathBuf)> {
     Ok((file, path))
 }
 
-fn main() -> io::Result<()> {
+fn print_usage() {
+    println!("Usage: multi-material-single-nozzle [options] [file]");
+    println!("Cleans up PrusaSlicer G-code to use single nozzle multi-material setup.");
+    println!("With no file (or `-`) the tool reads stdin and writes to stdout.");
+    println!("With a file argument the input is overwritten in place by default.");
+    println!();
+    println!("Options:");
+    println!("  --output <file>                     Write the result to <file>, leaving input intact");
+    println!("  --in-place                          Overwrite the input file (default when a file is given)");
+    println!("  --dry-run                           Report what would change without writing");
+    println!("  --firmware <marlin|reprap|klipper>  Pause command flavour (default: marlin)");
+    println!("  --pause-command <gcode>             Custom change command (newline-separated)");
+    println!("Version: {}", env!("CARGO_PKG_VERSION"));
+}
+
+/// Where the rewritten G-code is written.
+enum OutputMode {
+    /// Stream to stdout, leaving any input file untouched. The default when
+    /// reading from stdin (no path, or `-`).
+    Stdout,
+    /// Overwrite the input file in place. The default when a real input path
+    /// is given, matching how PrusaSlicer invokes post-processing scripts as
+    /// `script <path>` and expects the file edited in place.
+    InPlace,
+    /// Write to a separate file.
+    File(String),
+}
+
+/// Parsed command-line invocation.
+struct Cli {
+    input_path: Option<String>,
+    pause: Vec<String>,
+    output: OutputMode,
+    dry_run: bool,
+}
+
+impl Cli {
+    /// Scan `args` (including argv[0]) with a windowed flag pass. Returns
+    /// `Ok(None)` when help was requested and printed, and `Err` with a
+    /// human-readable message for malformed input.
+    fn parse(args: &[String]) -> Result<Option<Cli>, String> {
+        let mut input_path: Option<String> = None;
+        let mut firmware = Firmware::Marlin;
+        let mut custom_pause: Option<Vec<String>> = None;
+        let mut output_file: Option<String> = None;
+        let mut in_place = false;
+        let mut dry_run = false;
+
+        let mut i = 1;
+        while i < args.len() {
+            let arg = args[i].as_str();
+            match arg {
+                "-h" | "--help" => {
+                    print_usage();
+                    return Ok(None);
+                }
+                "--output" => {
+                    i += 1;
+                    let path = args.get(i).ok_or("--output requires a value")?;
+                    output_file = Some(path.clone());
+                }
+                "--in-place" => in_place = true,
+                "--dry-run" => dry_run = true,
+                "--firmware" => {
+                    i += 1;
+                    let name = args.get(i).ok_or("--firmware requires a value")?;
+                    firmware = match name.as_str() {
+                        "marlin" => Firmware::Marlin,
+                        "reprap" | "reprapfirmware" => Firmware::RepRap,
+                        "klipper" => Firmware::Klipper,
+                        other => return Err(format!("unknown firmware '{other}'")),
+                    };
+                }
+                "--pause-command" => {
+                    i += 1;
+                    let command = args.get(i).ok_or("--pause-command requires a value")?;
+                    custom_pause = Some(command.split('\n').map(str::to_string).collect());
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option '{arg}'")),
+                _ => {
+                    if input_path.is_some() {
+                        return Err("only one input file may be given".to_string());
+                    }
+                    input_path = Some(args[i].clone());
+                }
+            }
+            i += 1;
+        }
+
+        if in_place && output_file.is_some() {
+            return Err("--in-place and --output are mutually exclusive".to_string());
+        }
+
+        let stdin_mode = match &input_path {
+            Some(path) => path == "-",
+            None => true,
+        };
+        if in_place && stdin_mode {
+            return Err("--in-place requires an input file".to_string());
+        }
+
+        // An explicit `--pause-command` overrides the firmware preset.
+        if let Some(lines) = custom_pause {
+            firmware = Firmware::Custom(lines);
+        }
+
+        let output = match output_file {
+            Some(path) => OutputMode::File(path),
+            None if in_place || !stdin_mode => OutputMode::InPlace,
+            None => OutputMode::Stdout,
+        };
+
+        Ok(Some(Cli {
+            input_path,
+            pause: firmware.pause_sequence(),
+            output,
+            dry_run,
+        }))
+    }
+}
+
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 || args.iter().any(|arg| arg == "-h" || arg == "--help") {
-        println!("Usage: multi-material-single-nozzle <file>");
-        println!("Cleans up PrusaSlicer G-code to use single nozzle multi-material setup.");
-        println!("Version: {}", env!("CARGO_PKG_VERSION"));
-        return Ok(());
+    let cli = match Cli::parse(&args) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {message}");
+            eprintln!("Try '--help' for usage.");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let input_path = &args[1];
-    let mut input_file = File::open(input_path)?;
+fn run(cli: Cli) -> io::Result<()> {
+    // `total_toolchanges` and `wipe_tower` only appear in the footer, so the
+    // whole input is buffered once and fed through both the config and rewrite
+    // passes without needing a seekable source.
+    let buffer = read_input(cli.input_path.as_deref())?;
+    let config = SlicerConfig::read(buffer.as_slice())?;
 
-    let config = SlicerConfig::from_file(&input_file)?;
-    input_file.seek(SeekFrom::Start(0))?;
+    if cli.dry_run {
+        return report_dry_run(&config, buffer.as_slice());
+    }
 
-    let reader = BufReader::new(input_file);
+    // Fail loudly on malformed markers before touching any output.
+    let stats = validate(buffer.as_slice())?;
+    eprintln!("Validated {stats}.");
 
-    let (temp_file, temp_path) = tempfile("gcode")?;
-    let mut writer = BufWriter::new(temp_file);
+    match cli.output {
+        OutputMode::Stdout => {
+            let stdout = io::stdout();
+            let mut writer = BufWriter::new(stdout.lock());
+            rewrite(buffer.as_slice(), &mut writer, &config, &cli.pause)?;
+        }
+        OutputMode::InPlace | OutputMode::File(_) => {
+            let target = match &cli.output {
+                OutputMode::File(path) => path.clone(),
+                // `--in-place` without a path is rejected during parsing.
+                _ => cli.input_path.clone().expect("in-place mode has an input path"),
+            };
 
-    if config.wipe_tower {
-        replace_unloads(reader, &mut writer, config.total_toolchanges)?;
-    } else {
-        replace_toolchanges(reader, &mut writer)?;
+            let (temp_file, temp_path) = tempfile("gcode")?;
+            let mut writer = BufWriter::new(temp_file);
+            rewrite(buffer.as_slice(), &mut writer, &config, &cli.pause)?;
+            drop(writer);
+
+            fs::rename(&temp_path, &target)?;
+            eprintln!("Success: '{}' processed.", target);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the entire input into memory, from stdin when `path` is `None` or `-`.
+fn read_input(path: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match path {
+        Some(path) if path != "-" => {
+            File::open(path)?.read_to_end(&mut buffer)?;
+        }
+        _ => {
+            io::stdin().lock().read_to_end(&mut buffer)?;
+        }
     }
+    Ok(buffer)
+}
 
-    fs::rename(&temp_path, input_path)?;
+/// Report how many toolchange blocks would be replaced and whether wipe-tower
+/// mode was detected, without writing any output.
+fn report_dry_run(config: &SlicerConfig, reader: impl BufRead) -> io::Result<()> {
+    let mut starts = 0u32;
+    for line in reader.lines() {
+        if line?.starts_with("; CP TOOLCHANGE START") {
+            starts += 1;
+        }
+    }
 
-    println!("Success: '{}' processed.", input_path);
+    // In wipe-tower mode the trailing block is dropped entirely rather than
+    // replaced, so it is excluded from the count.
+    let replaced = if config.wipe_tower {
+        starts.min(config.total_toolchanges)
+    } else {
+        starts
+    };
+
+    println!("Dry run: {replaced} toolchange block(s) would be replaced.");
+    println!(
+        "Wipe tower mode: {}.",
+        if config.wipe_tower {
+            "detected"
+        } else {
+            "not detected"
+        }
+    );
     Ok(())
 }
 
+/// Dispatch to the appropriate rewriter for the detected slicer mode.
+fn rewrite(
+    reader: impl BufRead,
+    writer: &mut impl Write,
+    config: &SlicerConfig,
+    pause: &[String],
+) -> io::Result<()> {
+    if config.wipe_tower {
+        replace_unloads(reader, writer, config, pause)
+    } else {
+        replace_toolchanges(reader, writer, config, pause)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,7 +814,7 @@ M600
 ";
 
         let mut output = Vec::new();
-        replace_toolchanges(input.as_bytes(), &mut output).unwrap();
+        replace_toolchanges(input.as_bytes(), &mut output, &SlicerConfig::default(), &Firmware::Marlin.pause_sequence()).unwrap();
         let result = String::from_utf8(output).unwrap();
         assert_eq!(result, expected);
     }
@@ -298,20 +877,234 @@ M486 S-1
 G1 E-.8 F2100
 ";
 
+        let config = SlicerConfig {
+            total_toolchanges: 1,
+            ..Default::default()
+        };
         let mut output = Vec::new();
-        replace_unloads(input.as_bytes(), &mut output, 1).unwrap();
+        replace_unloads(input.as_bytes(), &mut output, &config, &Firmware::Marlin.pause_sequence()).unwrap();
         let result = String::from_utf8(output).unwrap();
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_replace_unloads_with_temperature() {
+        // As in `replace_toolchanges`, the temperature-aware change is deferred
+        // to the `Tn` move after END rather than emitted at the WIPE marker's
+        // position.
+        let input = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+; CP TOOLCHANGE START
+; toolchange #1
+; material : PLA -> PETG
+M220 S100
+; CP TOOLCHANGE UNLOAD
+G4 S0
+; CP TOOLCHANGE WIPE
+G92 E0
+; CP TOOLCHANGE END
+T1
+G1 X102.279 Y135.586 F7200
+";
+        let expected = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+; CP TOOLCHANGE START
+; toolchange #1
+; material : PLA -> PETG
+M220 S100
+G92 E0
+; CP TOOLCHANGE END
+M104 S240
+M600
+M109 S240
+T1
+G1 X102.279 Y135.586 F7200
+";
+
+        let config = SlicerConfig {
+            wipe_tower: true,
+            total_toolchanges: 1,
+            temperatures: vec![215, 240],
+            first_layer_temperatures: vec![220, 245],
+        };
+        let mut output = Vec::new();
+        replace_unloads(input.as_bytes(), &mut output, &config, &Firmware::Marlin.pause_sequence()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
     #[test]
     fn test_no_toolchanges() {
         let input = "G1 X10\nG1 Y10\n";
         let mut output = Vec::new();
-        replace_toolchanges(input.as_bytes(), &mut output).unwrap();
+        replace_toolchanges(input.as_bytes(), &mut output, &SlicerConfig::default(), &Firmware::Marlin.pause_sequence()).unwrap();
         assert_eq!(String::from_utf8(output).unwrap(), input);
     }
 
+    #[test]
+    fn test_replace_toolchanges_with_temperature() {
+        let input = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+G1 X149.27 Y134.713 E.46499
+; CP TOOLCHANGE START
+; toolchange #1
+; material : PLA -> PETG
+; CP TOOLCHANGE END
+T1
+G1 X102.279 Y135.586 F7200
+";
+        let expected = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+G1 X149.27 Y134.713 E.46499
+M104 S240
+M600
+M109 S240
+T1
+G1 X102.279 Y135.586 F7200
+";
+
+        let config = SlicerConfig {
+            temperatures: vec![215, 240],
+            first_layer_temperatures: vec![220, 245],
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        replace_toolchanges(input.as_bytes(), &mut output, &config, &Firmware::Marlin.pause_sequence()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_replace_toolchanges_with_temperature_no_tool_move() {
+        // Two blocks in a row with no `Tn` between them: the first block's
+        // pending change must not be dropped when the second one starts.
+        let input = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+; CP TOOLCHANGE START
+; material : PLA -> PETG
+; CP TOOLCHANGE END
+; CP TOOLCHANGE START
+; material : PETG -> PLA
+; CP TOOLCHANGE END
+G1 X102.279 Y135.586 F7200
+";
+        let expected = "\
+;LAYER_CHANGE
+;LAYER_CHANGE
+M600
+G1 X102.279 Y135.586 F7200
+M600
+";
+
+        let config = SlicerConfig {
+            temperatures: vec![215, 240],
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        replace_toolchanges(input.as_bytes(), &mut output, &config, &Firmware::Marlin.pause_sequence()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_klipper_pause_sequence() {
+        let input = "\
+G1 X10
+; CP TOOLCHANGE START
+; toolchange #1
+; CP TOOLCHANGE END
+G1 Y10
+";
+        let expected = "\
+G1 X10
+PAUSE
+G1 Y10
+";
+
+        let mut output = Vec::new();
+        replace_toolchanges(
+            input.as_bytes(),
+            &mut output,
+            &SlicerConfig::default(),
+            &Firmware::Klipper.pause_sequence(),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_validate_counts_markers() {
+        let input = "\
+; CP TOOLCHANGE START
+; CP TOOLCHANGE UNLOAD
+; CP TOOLCHANGE WIPE
+; CP TOOLCHANGE END
+; total toolchanges = 1
+; wipe_tower = 1
+";
+        let stats = validate(input.as_bytes()).unwrap();
+        assert_eq!(
+            stats,
+            ToolchangeStats {
+                starts: 1,
+                ends: 1,
+                unloads: 1,
+                wipes: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_accepts_unload_without_wipe() {
+        // The trailing wipe-tower block PrusaSlicer emits (and the rewriter
+        // drops entirely) has an UNLOAD with no matching WIPE before END.
+        let input = "\
+; CP TOOLCHANGE START
+; CP TOOLCHANGE UNLOAD
+; CP TOOLCHANGE END
+";
+        let stats = validate(input.as_bytes()).unwrap();
+        assert_eq!(
+            stats,
+            ToolchangeStats {
+                starts: 1,
+                ends: 1,
+                unloads: 1,
+                wipes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_wipe_without_unload() {
+        let input = "\
+; CP TOOLCHANGE START
+; CP TOOLCHANGE WIPE
+; CP TOOLCHANGE END
+";
+        let err = validate(input.as_bytes()).unwrap_err();
+        assert!(matches!(err, ValidationError::WipeWithoutUnload { line: 2 }));
+    }
+
+    #[test]
+    fn test_validate_rejects_count_mismatch() {
+        let input = "\
+; CP TOOLCHANGE START
+; CP TOOLCHANGE END
+; total toolchanges = 3
+";
+        let err = validate(input.as_bytes()).unwrap_err();
+        assert!(matches!(
+            err,
+            ValidationError::CountMismatch {
+                expected: 3,
+                found: 1
+            }
+        ));
+    }
+
     #[test]
     fn test_config_read() {
         let config_data = "\
@@ -333,6 +1126,8 @@ G1 E-.8 F2100
 
 ; prusaslicer_config = begin
 ; arc_fitting = emit_center
+; first_layer_temperature = 220,245
+; temperature = 215,240
 ; ...
 ; wipe_tower = 1
 ; prusaslicer_config = end
@@ -341,5 +1136,7 @@ G1 E-.8 F2100
         let config = SlicerConfig::read(config_data.as_bytes()).unwrap();
         assert_eq!(config.wipe_tower, true);
         assert_eq!(config.total_toolchanges, 4);
+        assert_eq!(config.temperatures, vec![215, 240]);
+        assert_eq!(config.first_layer_temperatures, vec![220, 245]);
     }
 }